@@ -1,23 +1,116 @@
-use anyhow::{anyhow, bail, Context, Result};
-use log::info;
-use reqwest::{header, Client};
+mod near_client;
+mod price_sources;
+
+use anyhow::{bail, Context, Result};
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use near_client::NearClient;
+use price_sources::{aggregate_mid_price, BinanceSource, CoinMarketCapSource, CoinbaseSource, PriceSource};
+use reqwest::Client;
 use serde::Deserialize;
 use std::fs::OpenOptions;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::path::Path;
-use std::process::Command;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message;
 
 const CONFIGURATION_ENV: &'static str = "CFG_PATH";
+const TESTNET_FLAG: &str = "--testnet";
+
+fn default_price_change_threshold() -> f64 {
+    0.001
+}
+
+fn default_min_push_interval_secs() -> u64 {
+    5
+}
+
+fn default_symbols() -> Vec<String> {
+    vec!["BTC".to_string()]
+}
+
+/// Which NEAR network the feeder pushes prices to. Mirrors `near-cli`'s
+/// convention of defaulting to mainnet unless told otherwise.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Default for Network {
+    fn default() -> Self {
+        Network::Mainnet
+    }
+}
+
+impl Network {
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "https://rpc.mainnet.near.org",
+            Network::Testnet => "https://rpc.testnet.near.org",
+        }
+    }
+
+    /// Suffix NEAR top-level accounts use on this network, e.g. `alice.near`
+    /// vs `alice.testnet`.
+    pub fn account_suffix(&self) -> &'static str {
+        match self {
+            Network::Mainnet => ".near",
+            Network::Testnet => ".testnet",
+        }
+    }
+
+    /// Subdirectory `near-cli` stores credentials under, e.g.
+    /// `~/.near-credentials/<network>/<account>.json`.
+    pub fn credentials_dir(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+        }
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub cmc_api_key: String,
     pub contract_id: String,
     pub signer_account_id: String,
+    /// Symbols to query from CoinMarketCap and push to the contract, e.g.
+    /// `["BTC", "ETH"]`. Defaults to `["BTC"]`.
+    #[serde(default = "default_symbols")]
+    pub symbols: Vec<String>,
+    /// NEAR network to target. Defaults to mainnet; overridden by the
+    /// `--testnet` CLI flag.
+    #[serde(default)]
+    pub network: Network,
+    /// Ed25519 secret key (`ed25519:...`) used to sign contract calls. When
+    /// unset, falls back to the `near-cli` credentials file for
+    /// `signer_account_id`.
+    #[serde(default)]
+    pub signer_secret_key: Option<String>,
+    /// Ticker WebSocket endpoint to stream prices from. When unset, the feeder
+    /// falls back to hourly REST polling only.
+    #[serde(default)]
+    pub ws_url: Option<String>,
+    /// Minimum relative price move (e.g. `0.001` = 0.1%) required before a
+    /// streamed update is pushed to the contract.
+    #[serde(default = "default_price_change_threshold")]
+    pub price_change_threshold: f64,
+    /// Minimum time between two pushes triggered by the WebSocket stream, so a
+    /// jittery ticker can't spam `near call`.
+    #[serde(default = "default_min_push_interval_secs")]
+    pub min_push_interval_secs: u64,
+    /// Percentage spread (e.g. `0.001` = 0.1%) used to derive synthetic bid
+    /// and ask quotes from the aggregated mid-price. When unset, only the
+    /// mid-price is published.
+    #[serde(default)]
+    pub spread_percent: Option<f64>,
 }
 
 impl Config {
+    /// Loads the config from `path` without validating it, so callers can
+    /// apply CLI overrides (e.g. `--testnet`) before calling [`Config::is_valid`].
     pub fn from_toml<T: std::clone::Clone + AsRef<Path>>(path: T) -> Result<Self> {
         info!("Loading configuration from toml file");
         let mut f = OpenOptions::new()
@@ -28,130 +121,189 @@ impl Config {
         f.read_to_end(&mut buffer)?;
         let config = toml::from_slice::<Self>(&buffer[..])
             .context("While parsing configuration from toml file.")?;
-        config.is_valid()?;
         Ok(config)
     }
 
     pub fn is_valid(&self) -> Result<()> {
+        let suffix = self.network.account_suffix();
+        if !self.signer_account_id.ends_with(suffix) {
+            bail!(
+                "signer_account_id '{}' does not look like a {:?} account (expected suffix '{}')",
+                self.signer_account_id,
+                self.network,
+                suffix
+            );
+        }
+        if !self.contract_id.ends_with(suffix) {
+            bail!(
+                "contract_id '{}' does not look like a {:?} account (expected suffix '{}')",
+                self.contract_id,
+                self.network,
+                suffix
+            );
+        }
+        if self.ws_url.is_some() && self.symbols.len() != 1 {
+            bail!(
+                "ws_url streams a single symbol, but {} symbols are configured ({:?}); \
+                 set exactly one symbol or drop ws_url and use REST polling",
+                self.symbols.len(),
+                self.symbols
+            );
+        }
         Ok(())
     }
 }
 
+/// A single tick from the exchange's ticker stream. Most ticker streams
+/// report price as a string to avoid floating point precision loss over the
+/// wire, so it's parsed into the same `f64` used everywhere else.
 #[derive(Deserialize, Debug, Clone)]
-pub struct ResponseBody {
-    data: DataBody,
+pub struct TickerMessage {
+    #[serde(rename = "c")]
+    price: String,
 }
 
-impl ResponseBody {
-    pub fn price(&self) -> &f64 {
-        &self.data.quote.usd.price
+impl TickerMessage {
+    pub fn price(&self) -> Result<f64> {
+        self.price
+            .parse()
+            .context("Invalid price in ticker message")
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-pub struct DataBody {
-    quote: QuoteBody,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct QuoteBody {
-    #[serde(rename = "USD")]
-    usd: CurrencyBody,
-}
-
-#[derive(Deserialize, Debug, Clone)]
-pub struct CurrencyBody {
+async fn near_set_last_price(
+    client: &NearClient,
+    symbol: &str,
     price: f64,
+    contract_id: &str,
+) -> Result<()> {
+    let outcome = client
+        .call_function(
+            contract_id,
+            "set_last_price",
+            serde_json::json!({ "symbol": symbol, "price": price }),
+        )
+        .await
+        .context("Error calling 'set_last_price'")?;
+    info!("set_last_price outcome: tx {}", outcome.transaction_hash);
+    Ok(())
 }
 
-fn near_login() -> Result<()> {
-    let cmd_output = Command::new("near")
-        .args(["login"])
-        .output()
-        .expect("failed to execute near-cli");
-
-    if cmd_output.status.success() {
-        std::io::stdout()
-            .write_all(&cmd_output.stdout)
-            .context("Error on trying write to stdout")
-            .unwrap();
-    } else {
-        bail!("Error on command 'near login': {}", unsafe {
-            std::str::from_utf8_unchecked(&cmd_output.stderr)
-        })
+/// Pushes `price` to the contract if it moved by at least
+/// `cfg.price_change_threshold` relative to `last_pushed` and at least
+/// `cfg.min_push_interval_secs` elapsed since `last_push_at`, debouncing
+/// otherwise. Updates both trackers on push.
+async fn maybe_push_price(
+    symbol: &str,
+    price: f64,
+    cfg: &Config,
+    near: &NearClient,
+    last_pushed: &mut Option<f64>,
+    last_push_at: &mut Instant,
+) -> Result<()> {
+    let moved_enough = match *last_pushed {
+        Some(previous) if previous != 0.0 => {
+            ((price - previous) / previous).abs() >= cfg.price_change_threshold
+        }
+        Some(_) => true,
+        None => true,
     };
+    let debounced = last_push_at.elapsed() < Duration::from_secs(cfg.min_push_interval_secs);
+
+    if moved_enough && !debounced {
+        info!("Streamed {} price = {}", symbol, price);
+        near_set_last_price(near, symbol, price, &cfg.contract_id).await?;
+        *last_pushed = Some(price);
+        *last_push_at = Instant::now();
+    }
     Ok(())
 }
 
-async fn init_req_client(api_token: &str) -> Result<Client> {
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "X-CMC_PRO_API_KEY",
-        header::HeaderValue::from_str(api_token)
-            .context("Invalid X-CMC_PRO_API_KEY header value")?,
-    );
-    headers.insert(
-        "Host",
-        header::HeaderValue::from_static("pro-api.coinmarketcap.com"),
-    );
-    headers.insert(
-        "Accept",
-        header::HeaderValue::from_static("application/json"),
-    );
-    headers.insert(
-        "Accept-Encoding",
-        header::HeaderValue::from_static("deflate, gzip"),
-    );
-
-    Ok(reqwest::Client::builder()
-        .default_headers(headers)
-        .gzip(true)
-        .deflate(true)
-        .build()?)
-}
+/// Opens the ticker WebSocket and pushes debounced price updates until the
+/// connection closes or errors. Returns on any disconnect so the caller can
+/// decide whether to fall back to REST and how long to back off before
+/// reconnecting.
+async fn stream_prices_once(
+    ws_url: &str,
+    symbol: &str,
+    cfg: &Config,
+    near: &NearClient,
+    last_pushed: &mut Option<f64>,
+    last_push_at: &mut Instant,
+) -> Result<()> {
+    info!("Connecting to ticker WebSocket at {}", ws_url);
+    let (ws_stream, _) = tokio_tungstenite::connect_async(ws_url)
+        .await
+        .context("Failed to connect to ticker WebSocket")?;
+    let (mut write, mut read) = ws_stream.split();
 
-async fn get_bitcoin_price(client: &Client) -> Result<f64> {
-    let response = client
-        .post("http://pro-api.coinmarketcap.com/v1/tools/price-conversion")
-        .query(&[("symbol", "BTC"), ("amount", "1")])
-        .send()
-        .await?;
-    if response.status().is_success() {
-        let body: ResponseBody = response.json().await?;
-        Ok(*body.price())
-    } else {
-        let err = anyhow!(
-            "Error status: {} with body:\n{}",
-            response.status(),
-            response.json::<serde_json::Value>().await?
-        );
-        Err(err)?
+    while let Some(msg) = read.next().await {
+        match msg.context("Error reading from ticker WebSocket")? {
+            Message::Text(text) => {
+                let tick: TickerMessage = serde_json::from_str(&text)
+                    .context("Failed to deserialize ticker message")?;
+                maybe_push_price(symbol, tick.price()?, cfg, near, last_pushed, last_push_at)
+                    .await?;
+            }
+            Message::Ping(payload) => write.send(Message::Pong(payload)).await?,
+            Message::Close(_) => bail!("Ticker WebSocket closed by server"),
+            _ => {}
+        }
     }
+    bail!("Ticker WebSocket stream ended")
 }
 
-fn near_set_last_price(price: f64, contract_id: &str, signer_id: &str) -> Result<()> {
-    let cmd_output = Command::new("near")
-        .args([
-            "call",
-            contract_id,
-            "set_last_price",
-            &format!("'{{\"price\":{}}}'", price),
-            "--accountId",
-            signer_id,
-        ])
-        .output()
-        .expect("failed to execute near-cli");
-
-    if cmd_output.status.success() {
-        unsafe { std::str::from_utf8_unchecked(&cmd_output.stdout) }
-            .lines()
-            .for_each(|line| info!("{}", line));
-    } else {
-        bail!("Error on command 'near call set_last_price': {}", unsafe {
-            std::str::from_utf8_unchecked(&cmd_output.stderr)
-        })
-    };
-    Ok(())
+/// Streams prices for `cfg.symbols`'s single configured symbol (enforced by
+/// [`Config::is_valid`]) from `cfg.ws_url`, with automatic reconnect/backoff,
+/// falling back to a single multi-source aggregated REST poll (the same
+/// `PriceSource`/`aggregate_mid_price` path the non-streaming loop uses) for
+/// each tick where the socket is down.
+async fn stream_prices(
+    ws_url: &str,
+    cfg: &Config,
+    near: &NearClient,
+    client: &Client,
+    sources: &[Box<dyn PriceSource>],
+) -> Result<()> {
+    let symbol = cfg
+        .symbols
+        .first()
+        .expect("Config::is_valid requires exactly one symbol when ws_url is set");
+    let mut backoff = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+    let mut last_pushed: Option<f64> = None;
+    let mut last_push_at = Instant::now() - Duration::from_secs(cfg.min_push_interval_secs);
+
+    loop {
+        if let Err(e) =
+            stream_prices_once(ws_url, symbol, cfg, near, &mut last_pushed, &mut last_push_at)
+                .await
+        {
+            warn!(
+                "Ticker WebSocket unavailable ({}), falling back to REST for this tick",
+                e
+            );
+            match aggregate_mid_price(sources, client, symbol).await {
+                Ok(price) => {
+                    maybe_push_price(
+                        symbol,
+                        price,
+                        cfg,
+                        near,
+                        &mut last_pushed,
+                        &mut last_push_at,
+                    )
+                    .await?
+                }
+                Err(e) => warn!("REST fallback also failed: {}", e),
+            }
+            info!("Reconnecting to ticker WebSocket in {:?}", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        } else {
+            backoff = Duration::from_secs(1);
+        }
+    }
 }
 
 #[tokio::main]
@@ -162,13 +314,70 @@ async fn main() -> Result<()> {
         .unwrap();
     let cfg_path = std::env::var(CONFIGURATION_ENV)
         .expect(&format!("Environment '{}' did not set", CONFIGURATION_ENV));
-    let cfg = Config::from_toml(cfg_path).unwrap();
-    near_login()?;
-    let client = init_req_client(&cfg.cmc_api_key).await?;
-    loop {
-        let current_price = get_bitcoin_price(&client).await?;
-        info!("Current BTC price = {}", &current_price);
-        near_set_last_price(current_price, &cfg.contract_id, &cfg.signer_account_id).unwrap();
-        tokio::time::sleep(Duration::from_secs(3600)).await;
+    let mut cfg = Config::from_toml(cfg_path).unwrap();
+    if std::env::args().any(|arg| arg == TESTNET_FLAG) {
+        info!("'{}' passed, overriding network to testnet", TESTNET_FLAG);
+        cfg.network = Network::Testnet;
+    }
+    cfg.is_valid()?;
+    let near = NearClient::new(&cfg)?;
+    let client = Client::new();
+    let sources: Vec<Box<dyn PriceSource>> = vec![
+        Box::new(CoinMarketCapSource::new(cfg.cmc_api_key.clone())),
+        Box::new(BinanceSource),
+        Box::new(CoinbaseSource),
+    ];
+
+    if let Some(ws_url) = cfg.ws_url.clone() {
+        stream_prices(&ws_url, &cfg, &near, &client, &sources).await?;
+    } else {
+        loop {
+            for source in &sources {
+                if let Err(e) = source.prefetch(&client, &cfg.symbols).await {
+                    warn!("Prefetch failed for price source '{}': {}", source.name(), e);
+                }
+            }
+
+            for symbol in &cfg.symbols {
+                match aggregate_mid_price(&sources, &client, symbol).await {
+                    Ok(mid) => {
+                        info!("Aggregated {} mid-price = {}", symbol, mid);
+                        if let Err(e) =
+                            near_set_last_price(&near, symbol, mid, &cfg.contract_id).await
+                        {
+                            warn!("Failed to push {} price: {}", symbol, e);
+                            continue;
+                        }
+
+                        if let Some(spread_percent) = cfg.spread_percent {
+                            let bid = mid * (1.0 - spread_percent / 2.0);
+                            let ask = mid * (1.0 + spread_percent / 2.0);
+                            if let Err(e) = near_set_last_price(
+                                &near,
+                                &format!("{}_BID", symbol),
+                                bid,
+                                &cfg.contract_id,
+                            )
+                            .await
+                            {
+                                warn!("Failed to push {} bid price: {}", symbol, e);
+                            }
+                            if let Err(e) = near_set_last_price(
+                                &near,
+                                &format!("{}_ASK", symbol),
+                                ask,
+                                &cfg.contract_id,
+                            )
+                            .await
+                            {
+                                warn!("Failed to push {} ask price: {}", symbol, e);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Failed to aggregate price for {}: {}", symbol, e),
+                }
+            }
+            tokio::time::sleep(Duration::from_secs(3600)).await;
+        }
     }
 }
@@ -0,0 +1,145 @@
+//! A minimal NEAR JSON-RPC client used to sign and broadcast the feeder's
+//! contract calls in-process, replacing the old `near-cli` subprocess.
+
+use crate::{Config, Network};
+use anyhow::{anyhow, bail, Context, Result};
+use near_crypto::{InMemorySigner, KeyFile, PublicKey, Signer};
+use near_jsonrpc_client::{methods, JsonRpcClient};
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::{Action, FunctionCallAction, Transaction};
+use near_primitives::types::{AccountId, BlockReference};
+use near_primitives::views::{FinalExecutionStatus, QueryRequest};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_GAS: u64 = 10_000_000_000_000;
+
+/// Outcome of a submitted transaction, surfaced as typed data instead of the
+/// scraped `near-cli` stdout the subprocess path used to return.
+#[derive(Debug, Clone)]
+pub struct CallOutcome {
+    pub transaction_hash: CryptoHash,
+    pub status: FinalExecutionStatus,
+}
+
+impl CallOutcome {
+    pub fn succeeded(&self) -> bool {
+        matches!(self.status, FinalExecutionStatus::SuccessValue(_))
+    }
+}
+
+/// Signs and broadcasts contract calls against a NEAR RPC endpoint using a
+/// key loaded once at startup, rather than shelling out to `near-cli` for
+/// every call.
+pub struct NearClient {
+    rpc: JsonRpcClient,
+    signer: InMemorySigner,
+}
+
+impl NearClient {
+    /// Builds a client targeting `cfg.network`, signing with a key loaded
+    /// directly out of `cfg` (`signer_secret_key`) or, failing that, from the
+    /// default `near-cli` credentials file for `cfg.signer_account_id`.
+    pub fn new(cfg: &Config) -> Result<Self> {
+        let account_id: AccountId = cfg
+            .signer_account_id
+            .parse()
+            .context("Invalid signer_account_id")?;
+
+        let signer = match &cfg.signer_secret_key {
+            Some(secret_key) => {
+                let secret_key = secret_key
+                    .parse()
+                    .context("Invalid signer_secret_key in config")?;
+                InMemorySigner::from_secret_key(account_id, secret_key)
+            }
+            None => {
+                let key_path = default_credentials_path(&account_id, cfg.network);
+                let key_file = KeyFile::from_file(&key_path).with_context(|| {
+                    format!(
+                        "No signer_secret_key in config and no credentials file at {}",
+                        key_path.display()
+                    )
+                })?;
+                InMemorySigner::from(key_file)
+            }
+        };
+
+        Ok(Self {
+            rpc: JsonRpcClient::connect(cfg.network.rpc_url()),
+            signer,
+        })
+    }
+
+    /// Signs and broadcasts a `FunctionCall` action against `contract_id`,
+    /// waiting for the transaction to finalize and returning its outcome.
+    pub async fn call_function(
+        &self,
+        contract_id: &str,
+        method_name: &str,
+        args: serde_json::Value,
+    ) -> Result<CallOutcome> {
+        let contract_id: AccountId = contract_id.parse().context("Invalid contract_id")?;
+        let public_key: PublicKey = self.signer.public_key.clone();
+
+        let access_key = self
+            .rpc
+            .call(methods::query::RpcQueryRequest {
+                block_reference: BlockReference::latest(),
+                request: QueryRequest::ViewAccessKey {
+                    account_id: self.signer.account_id.clone(),
+                    public_key: public_key.clone(),
+                },
+            })
+            .await
+            .context("Failed to fetch signer access key from RPC")?;
+
+        let nonce = match access_key.kind {
+            QueryResponseKind::AccessKey(access_key) => access_key.nonce + 1,
+            _ => bail!("Unexpected RPC response while querying access key"),
+        };
+
+        let transaction = Transaction {
+            signer_id: self.signer.account_id.clone(),
+            public_key,
+            nonce,
+            receiver_id: contract_id,
+            block_hash: access_key.block_hash,
+            actions: vec![Action::FunctionCall(FunctionCallAction {
+                method_name: method_name.to_string(),
+                args: serde_json::to_vec(&args).context("Failed to serialize call args")?,
+                gas: DEFAULT_GAS,
+                deposit: 0,
+            })],
+        };
+
+        let outcome = self
+            .rpc
+            .call(methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest {
+                signed_transaction: transaction.sign(&self.signer),
+            })
+            .await
+            .context("Failed to broadcast transaction")?;
+
+        if !matches!(outcome.status, FinalExecutionStatus::SuccessValue(_)) {
+            return Err(anyhow!(
+                "Transaction {} did not succeed: {:?}",
+                outcome.transaction.hash,
+                outcome.status
+            ));
+        }
+
+        Ok(CallOutcome {
+            transaction_hash: outcome.transaction.hash,
+            status: outcome.status,
+        })
+    }
+}
+
+fn default_credentials_path(account_id: &AccountId, network: Network) -> PathBuf {
+    dirs::home_dir()
+        .unwrap_or_else(|| Path::new(".").to_path_buf())
+        .join(".near-credentials")
+        .join(network.credentials_dir())
+        .join(format!("{}.json", account_id))
+}
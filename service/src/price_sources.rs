@@ -0,0 +1,299 @@
+//! Pluggable price providers. Querying several sources and aggregating them
+//! removes the single-provider trust assumption a lone CoinMarketCap request
+//! carries.
+
+use anyhow::{anyhow, Context, Result};
+use futures_util::future::{join_all, BoxFuture};
+use futures_util::FutureExt;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::sync::Mutex;
+
+/// Relative distance from the median beyond which a quote is discarded as an
+/// outlier before the mid-price is computed.
+const OUTLIER_THRESHOLD: f64 = 0.05;
+
+/// A source the feeder can fetch a single symbol's USD price from.
+pub trait PriceSource: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn fetch_price<'a>(&'a self, client: &'a Client, symbol: &'a str) -> BoxFuture<'a, Result<f64>>;
+
+    /// Called once per poll cycle with every symbol that will be fetched, so
+    /// sources that can batch (e.g. CoinMarketCap's `quotes/latest`) can issue
+    /// a single request up front instead of one per symbol. Sources that
+    /// can't batch just no-op here and fetch per-symbol as usual.
+    fn prefetch<'a>(&'a self, _client: &'a Client, _symbols: &'a [String]) -> BoxFuture<'a, Result<()>> {
+        async { Ok(()) }.boxed()
+    }
+}
+
+pub struct CoinMarketCapSource {
+    api_key: String,
+    /// Prices fetched by the last [`Self::prefetch`] call, keyed by symbol.
+    /// `fetch_price` reads from here first so a poll cycle over many symbols
+    /// costs one batched CoinMarketCap request instead of one per symbol.
+    cache: Mutex<HashMap<String, f64>>,
+}
+
+impl CoinMarketCapSource {
+    pub fn new(api_key: String) -> Self {
+        Self {
+            api_key,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn fetch_batch(&self, client: &Client, symbols: &[String]) -> Result<HashMap<String, f64>> {
+        let response = client
+            .get("http://pro-api.coinmarketcap.com/v1/cryptocurrency/quotes/latest")
+            .header("X-CMC_PRO_API_KEY", &self.api_key)
+            .query(&[("symbol", symbols.join(","))])
+            .send()
+            .await?;
+        let body: CmcResponseBody = response.json().await?;
+        Ok(body
+            .data
+            .into_iter()
+            .map(|(symbol, entry)| (symbol, entry.quote.usd.price))
+            .collect())
+    }
+}
+
+impl PriceSource for CoinMarketCapSource {
+    fn name(&self) -> &'static str {
+        "coinmarketcap"
+    }
+
+    fn fetch_price<'a>(&'a self, client: &'a Client, symbol: &'a str) -> BoxFuture<'a, Result<f64>> {
+        async move {
+            if let Some(&price) = self.cache.lock().await.get(symbol) {
+                return Ok(price);
+            }
+            // No prefetched batch covers this symbol (e.g. called without a
+            // preceding `prefetch`); fall back to a single-symbol request.
+            self.fetch_batch(client, &[symbol.to_string()])
+                .await?
+                .remove(symbol)
+                .ok_or_else(|| anyhow!("coinmarketcap returned no price for {}", symbol))
+        }
+        .boxed()
+    }
+
+    fn prefetch<'a>(&'a self, client: &'a Client, symbols: &'a [String]) -> BoxFuture<'a, Result<()>> {
+        async move {
+            self.cache.lock().await.clear();
+            let prices = self.fetch_batch(client, symbols).await?;
+            *self.cache.lock().await = prices;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct CmcResponseBody {
+    data: HashMap<String, CmcDataBody>,
+}
+
+#[derive(Deserialize)]
+struct CmcDataBody {
+    quote: CmcQuoteBody,
+}
+
+#[derive(Deserialize)]
+struct CmcQuoteBody {
+    #[serde(rename = "USD")]
+    usd: CmcCurrencyBody,
+}
+
+#[derive(Deserialize)]
+struct CmcCurrencyBody {
+    price: f64,
+}
+
+pub struct BinanceSource;
+
+impl PriceSource for BinanceSource {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn fetch_price<'a>(&'a self, client: &'a Client, symbol: &'a str) -> BoxFuture<'a, Result<f64>> {
+        async move {
+            let response = client
+                .get("https://api.binance.com/api/v3/ticker/price")
+                .query(&[("symbol", format!("{}USDT", symbol))])
+                .send()
+                .await?;
+            let body: BinancePriceBody = response.json().await?;
+            body.price.parse().context("Invalid price from binance")
+        }
+        .boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct BinancePriceBody {
+    price: String,
+}
+
+pub struct CoinbaseSource;
+
+impl PriceSource for CoinbaseSource {
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    fn fetch_price<'a>(&'a self, client: &'a Client, symbol: &'a str) -> BoxFuture<'a, Result<f64>> {
+        async move {
+            let response = client
+                .get(format!(
+                    "https://api.coinbase.com/v2/prices/{}-USD/spot",
+                    symbol
+                ))
+                .send()
+                .await?;
+            let body: CoinbasePriceBody = response.json().await?;
+            body.data
+                .amount
+                .parse()
+                .context("Invalid price from coinbase")
+        }
+        .boxed()
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbasePriceBody {
+    data: CoinbaseAmountBody,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseAmountBody {
+    amount: String,
+}
+
+/// Fetches `symbol` from every source concurrently, discards quotes more
+/// than [`OUTLIER_THRESHOLD`] away from the median, and returns the mean of
+/// what remains as the aggregated mid-price.
+pub async fn aggregate_mid_price(
+    sources: &[Box<dyn PriceSource>],
+    client: &Client,
+    symbol: &str,
+) -> Result<f64> {
+    let results = join_all(sources.iter().map(|source| async move {
+        (source.name(), source.fetch_price(client, symbol).await)
+    }))
+    .await;
+
+    let mut prices = Vec::with_capacity(results.len());
+    for (name, result) in results {
+        match result {
+            Ok(price) => prices.push(price),
+            Err(e) => log::warn!("Price source '{}' failed for {}: {}", name, symbol, e),
+        }
+    }
+    if prices.is_empty() {
+        anyhow::bail!("No price source returned a price for {}", symbol);
+    }
+
+    prices.sort_by(|a, b| a.partial_cmp(b).expect("Price sources never return NaN"));
+    let median = prices[prices.len() / 2];
+    let inliers: Vec<f64> = prices
+        .into_iter()
+        .filter(|price| ((price - median) / median).abs() <= OUTLIER_THRESHOLD)
+        .collect();
+
+    Ok(inliers.iter().sum::<f64>() / inliers.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedSource {
+        name: &'static str,
+        price: Result<f64>,
+    }
+
+    impl PriceSource for FixedSource {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn fetch_price<'a>(
+            &'a self,
+            _client: &'a Client,
+            _symbol: &'a str,
+        ) -> BoxFuture<'a, Result<f64>> {
+            let result = match &self.price {
+                Ok(price) => Ok(*price),
+                Err(e) => Err(anyhow!("{}", e)),
+            };
+            async move { result }.boxed()
+        }
+    }
+
+    fn fixed_sources(prices: &[f64]) -> Vec<Box<dyn PriceSource>> {
+        const NAMES: [&str; 4] = ["source0", "source1", "source2", "source3"];
+        prices
+            .iter()
+            .zip(NAMES.iter())
+            .map(|(&price, &name)| {
+                Box::new(FixedSource {
+                    name,
+                    price: Ok(price),
+                }) as Box<dyn PriceSource>
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn agrees_averages_all_sources() {
+        let sources = fixed_sources(&[100.0, 101.0, 99.0]);
+        let mid = aggregate_mid_price(&sources, &Client::new(), "BTC")
+            .await
+            .unwrap();
+        assert_eq!(mid, (100.0 + 101.0 + 99.0) / 3.0);
+    }
+
+    #[tokio::test]
+    async fn discards_outlier_before_averaging() {
+        let sources = fixed_sources(&[100.0, 101.0, 500.0]);
+        let mid = aggregate_mid_price(&sources, &Client::new(), "BTC")
+            .await
+            .unwrap();
+        assert_eq!(mid, (100.0 + 101.0) / 2.0);
+    }
+
+    #[tokio::test]
+    async fn bails_when_every_source_fails() {
+        let sources: Vec<Box<dyn PriceSource>> = vec![
+            Box::new(FixedSource {
+                name: "source0",
+                price: Err(anyhow!("timed out")),
+            }),
+            Box::new(FixedSource {
+                name: "source1",
+                price: Err(anyhow!("bad response")),
+            }),
+        ];
+        let err = aggregate_mid_price(&sources, &Client::new(), "BTC")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("No price source returned a price"));
+    }
+
+    #[tokio::test]
+    async fn two_source_split_picks_upper_median() {
+        // With an even number of sources, `prices[len / 2]` takes the upper of
+        // the two middle values as the median; here it's also far enough from
+        // the lower quote to discard it as an outlier, leaving only itself.
+        let sources = fixed_sources(&[100.0, 200.0]);
+        let mid = aggregate_mid_price(&sources, &Client::new(), "BTC")
+            .await
+            .unwrap();
+        assert_eq!(mid, 200.0);
+    }
+}
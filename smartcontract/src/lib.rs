@@ -1,57 +1,173 @@
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::Vector;
+use near_sdk::collections::{UnorderedMap, Vector};
 use near_sdk::{env, log, near_bindgen, setup_alloc};
 use std::ops::AddAssign;
 
-const LAST_NUMBERS_FOR_AVERAGE: u64 = 5;
+const DEFAULT_WINDOW_SIZE: u64 = 5;
 
 setup_alloc!();
 
+/// A price observation together with the on-chain time it was recorded,
+/// needed to weight observations by how long they were the active price.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy, Debug, PartialEq)]
+pub struct PriceRecord {
+    price: f64,
+    timestamp: u64,
+}
+
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize)]
 pub struct AveragePrice {
-    records: Vector<f64>,
+    records: UnorderedMap<String, Vector<PriceRecord>>,
+    window_size: u64,
+    owner_id: String,
 }
 
-impl Default for AveragePrice {
-    fn default() -> Self {
+#[near_bindgen]
+impl AveragePrice {
+    #[init]
+    pub fn new(owner_id: String) -> Self {
+        assert!(!env::state_exists(), "Contract is already initialized");
         Self {
-            records: Vector::new::<&[u8]>("qwerty".as_ref()),
+            records: UnorderedMap::new::<&[u8]>("qwerty".as_ref()),
+            window_size: DEFAULT_WINDOW_SIZE,
+            owner_id,
         }
     }
-}
 
-#[near_bindgen]
-impl AveragePrice {
+    /// Lets the deployer account tune the number of trailing records used by
+    /// `get_average_price` and `get_median_price`, without a redeploy.
+    pub fn set_window_size(&mut self, window_size: u64) {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.owner_id,
+            "Only the owner account may change window_size"
+        );
+        assert!(window_size > 0, "window_size must be greater than zero");
+        self.window_size = window_size;
+    }
+
     #[payable]
-    pub fn set_last_price(&mut self, price: &f64) {
+    pub fn set_last_price(&mut self, symbol: String, price: &f64) {
         if !price.is_normal() {
             env::panic(b"Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN");
         }
-        log!("set_last_price with price {}", price);
-        self.records.push(price);
+        log!("set_last_price with symbol {} price {}", symbol, price);
+        let mut records = self
+            .records
+            .get(&symbol)
+            .unwrap_or_else(|| Vector::new(Self::records_prefix(&symbol)));
+        records.push(&PriceRecord {
+            price: *price,
+            timestamp: env::block_timestamp(),
+        });
+        self.records.insert(&symbol, &records);
     }
 
-    pub fn get_average_price(&self) -> Option<f64> {
-        log!("get_average_price");
-        if self.records.len() < LAST_NUMBERS_FOR_AVERAGE {
-            let sum: f64 = self.records.iter().sum();
+    /// Plain arithmetic mean of the last `window_size` records. Kept for
+    /// backward compatibility; prefer [`AveragePrice::get_twap`] for feeds
+    /// with irregular update intervals, or [`AveragePrice::get_median_price`]
+    /// for robustness against single-record spikes.
+    pub fn get_average_price(&self, symbol: String) -> Option<f64> {
+        log!("get_average_price for {}", symbol);
+        let records = self.records.get(&symbol)?;
+        if records.len() < self.window_size {
+            let sum: f64 = records.iter().map(|r| r.price).sum();
             if sum == 0.0 {
                 env::panic(b"No records. Unable to calculate average value.");
             }
-            Some(dbg!(sum) / dbg!(self.records.len() as f64))
+            Some(sum / records.len() as f64)
         } else {
             let mut sum = 0_f64;
-            for index in (self.records.len() - LAST_NUMBERS_FOR_AVERAGE)..self.records.len() {
-                let value = self
-                    .records
+            for index in (records.len() - self.window_size)..records.len() {
+                let value = records
                     .get(index)
                     .expect("Unexpected error: Array index out of bounds.");
-                sum.add_assign(value);
+                sum.add_assign(value.price);
             }
-            Some(sum / LAST_NUMBERS_FOR_AVERAGE as f64)
+            Some(sum / self.window_size as f64)
         }
     }
+
+    /// Median of the last `window_size` records, far more robust than
+    /// `get_average_price` against the occasional single-outlier spike a
+    /// price feed emits. Averages the two central values for an even count.
+    pub fn get_median_price(&self, symbol: String) -> Option<f64> {
+        log!("get_median_price for {}", symbol);
+        let records = self.records.get(&symbol)?;
+        if records.is_empty() {
+            return None;
+        }
+        let window = self.window_size.min(records.len());
+        let mut prices: Vec<f64> = ((records.len() - window)..records.len())
+            .map(|index| {
+                records
+                    .get(index)
+                    .expect("Unexpected error: Array index out of bounds.")
+                    .price
+            })
+            .collect();
+        prices.sort_by(|a, b| a.partial_cmp(b).expect("Stored prices are never NaN"));
+
+        let mid = prices.len() / 2;
+        if prices.len() % 2 == 0 {
+            Some((prices[mid - 1] + prices[mid]) / 2.0)
+        } else {
+            Some(prices[mid])
+        }
+    }
+
+    /// Time-weighted average price over the trailing `window_ns` nanoseconds.
+    /// Each record is weighted by how long it remained the latest price,
+    /// with the final record's segment extending to the current block
+    /// timestamp. Returns `None` if no record falls inside the window.
+    pub fn get_twap(&self, symbol: String, window_ns: u64) -> Option<f64> {
+        log!("get_twap for {} over {}ns", symbol, window_ns);
+        let records = self.records.get(&symbol)?;
+        let now = env::block_timestamp();
+        let window_start = now.saturating_sub(window_ns);
+
+        let mut in_window: Vec<PriceRecord> = (0..records.len())
+            .map(|index| {
+                records
+                    .get(index)
+                    .expect("Unexpected error: Array index out of bounds.")
+            })
+            .filter(|record| record.timestamp >= window_start)
+            .collect();
+        if in_window.is_empty() {
+            return None;
+        }
+        in_window.sort_by_key(|record| record.timestamp);
+
+        let total_duration = now.saturating_sub(in_window[0].timestamp);
+        if total_duration == 0 {
+            return Some(in_window[0].price);
+        }
+
+        let mut weighted_sum = 0_f64;
+        for i in 0..in_window.len() {
+            let segment_end = in_window
+                .get(i + 1)
+                .map(|next| next.timestamp)
+                .unwrap_or(now);
+            weighted_sum += in_window[i].price * (segment_end - in_window[i].timestamp) as f64;
+        }
+        Some(weighted_sum / total_duration as f64)
+    }
+
+    /// Symbols that have at least one recorded price.
+    pub fn supported_symbols(&self) -> Vec<String> {
+        self.records.keys().collect()
+    }
+}
+
+impl AveragePrice {
+    /// Storage prefix for a symbol's own `Vector`, kept distinct from the
+    /// outer map's `"qwerty"` prefix and from every other symbol's.
+    fn records_prefix(symbol: &str) -> Vec<u8> {
+        format!("r:{}", symbol).into_bytes()
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -66,6 +182,24 @@ mod tests {
     fn get_context(is_view: bool) -> VMContext {
         VMContextBuilder::new()
             .signer_account_id("vkarnaukhov.testnet".try_into().unwrap())
+            .predecessor_account_id("vkarnaukhov.testnet".try_into().unwrap())
+            .is_view(is_view)
+            .build()
+    }
+
+    fn get_context_at(is_view: bool, block_timestamp: u64) -> VMContext {
+        VMContextBuilder::new()
+            .signer_account_id("vkarnaukhov.testnet".try_into().unwrap())
+            .predecessor_account_id("vkarnaukhov.testnet".try_into().unwrap())
+            .is_view(is_view)
+            .block_timestamp(block_timestamp)
+            .build()
+    }
+
+    fn get_context_as(is_view: bool, predecessor_account_id: &str) -> VMContext {
+        VMContextBuilder::new()
+            .signer_account_id("vkarnaukhov.testnet".try_into().unwrap())
+            .predecessor_account_id(predecessor_account_id.try_into().unwrap())
             .is_view(is_view)
             .build()
     }
@@ -75,8 +209,8 @@ mod tests {
     fn set_nan_value() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&f64::NAN);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &f64::NAN);
         assert_eq!(get_logs(), vec!["Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN"]);
     }
 
@@ -85,8 +219,8 @@ mod tests {
     fn set_neg_infinity_value() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&f64::NEG_INFINITY);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &f64::NEG_INFINITY);
         assert_eq!(get_logs(), vec!["Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN"]);
     }
 
@@ -95,8 +229,8 @@ mod tests {
     fn set_infinity_value() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&f64::INFINITY);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &f64::INFINITY);
         assert_eq!(get_logs(), vec!["Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN"]);
     }
 
@@ -105,8 +239,8 @@ mod tests {
     fn set_zero_value() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&0.0);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &0.0);
         assert_eq!(get_logs(), vec!["Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN"]);
     }
 
@@ -115,34 +249,173 @@ mod tests {
     fn set_negative_value() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&-1.0);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &-1.0);
         assert_eq!(get_logs(), vec!["Method set_last_price doesn't accept the number is neither zero, infinite, subnormal, or NaN"]);
     }
 
     #[test]
-    #[should_panic]
     fn get_average_price_on_empty() {
         let context = get_context(true);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.get_average_price().unwrap();
+        let contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        assert_eq!(contract.get_average_price("BTC".to_string()), None);
+    }
+
+    #[test]
+    fn get_average() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &123.0);
+        contract.set_last_price("BTC".to_string(), &124.1);
+        contract.set_last_price("BTC".to_string(), &123.2345);
+        contract.set_last_price("BTC".to_string(), &3453.1284);
+        contract.set_last_price("BTC".to_string(), &123.23745);
         assert_eq!(
-            get_logs(),
-            vec!["No records. Unable to calculate average value."]
+            789.34007,
+            contract.get_average_price("BTC".to_string()).unwrap()
         )
     }
 
     #[test]
-    fn get_average() {
+    fn get_average_keeps_symbols_independent() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &100.0);
+        contract.set_last_price("ETH".to_string(), &10.0);
+        assert_eq!(contract.get_average_price("BTC".to_string()), Some(100.0));
+        assert_eq!(contract.get_average_price("ETH".to_string()), Some(10.0));
+        assert_eq!(
+            contract.supported_symbols(),
+            vec!["BTC".to_string(), "ETH".to_string()]
+        );
+    }
+
+    #[test]
+    fn get_twap_on_empty_window() {
+        let context = get_context_at(true, 1_000_000_000);
+        testing_env!(context);
+        let contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        assert_eq!(contract.get_twap("BTC".to_string(), 60_000_000_000), None);
+    }
+
+    #[test]
+    fn get_twap_single_record_returns_that_price() {
+        let context = get_context_at(false, 1_000_000_000);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &100.0);
+        assert_eq!(
+            contract.get_twap("BTC".to_string(), 60_000_000_000),
+            Some(100.0)
+        );
+    }
+
+    #[test]
+    fn get_twap_weighs_by_elapsed_time() {
+        testing_env!(get_context_at(false, 0));
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+
+        contract.set_last_price("BTC".to_string(), &100.0);
+
+        // price stayed 100 for 2s, then moves to 200 for 1s before "now".
+        testing_env!(get_context_at(false, 2_000_000_000));
+        contract.set_last_price("BTC".to_string(), &200.0);
+
+        testing_env!(get_context_at(true, 3_000_000_000));
+        // twap = (100 * 2s + 200 * 1s) / 3s = 133.33...
+        let twap = contract
+            .get_twap("BTC".to_string(), 60_000_000_000)
+            .unwrap();
+        assert!((twap - 133.33333333333334).abs() < 1e-9);
+    }
+
+    #[test]
+    fn get_twap_excludes_records_outside_window() {
+        testing_env!(get_context_at(false, 0));
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+
+        contract.set_last_price("BTC".to_string(), &100.0);
+
+        testing_env!(get_context_at(false, 10_000_000_000));
+        contract.set_last_price("BTC".to_string(), &200.0);
+
+        testing_env!(get_context_at(true, 11_000_000_000));
+        // A 1s window only sees the most recent record.
+        assert_eq!(
+            contract.get_twap("BTC".to_string(), 1_000_000_000),
+            Some(200.0)
+        );
+    }
+
+    #[test]
+    fn set_window_size_by_owner() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_window_size(3);
+        contract.set_last_price("BTC".to_string(), &1.0);
+        contract.set_last_price("BTC".to_string(), &2.0);
+        contract.set_last_price("BTC".to_string(), &3.0);
+        contract.set_last_price("BTC".to_string(), &300.0);
+        // window_size 3 means only the last 3 records (2, 3, 300) count.
+        assert_eq!(
+            contract.get_average_price("BTC".to_string()),
+            Some(101.66666666666667)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the owner account may change window_size")]
+    fn set_window_size_rejects_non_owner() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+
+        testing_env!(get_context_as(false, "impostor.testnet"));
+        contract.set_window_size(3);
+    }
+
+    #[test]
+    #[should_panic(expected = "window_size must be greater than zero")]
+    fn set_window_size_rejects_zero() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_window_size(0);
+    }
+
+    #[test]
+    fn get_median_price_on_empty() {
+        let context = get_context(true);
+        testing_env!(context);
+        let contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        assert_eq!(contract.get_median_price("BTC".to_string()), None);
+    }
+
+    #[test]
+    fn get_median_price_odd_count() {
+        let context = get_context(false);
+        testing_env!(context);
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_last_price("BTC".to_string(), &1.0);
+        contract.set_last_price("BTC".to_string(), &500.0);
+        contract.set_last_price("BTC".to_string(), &2.0);
+        assert_eq!(contract.get_median_price("BTC".to_string()), Some(2.0));
+    }
+
+    #[test]
+    fn get_median_price_even_count_averages_center() {
         let context = get_context(false);
         testing_env!(context);
-        let mut contract = AveragePrice::default();
-        contract.set_last_price(&123.0);
-        contract.set_last_price(&124.1);
-        contract.set_last_price(&123.2345);
-        contract.set_last_price(&3453.1284);
-        contract.set_last_price(&123.23745);
-        assert_eq!(789.34007, contract.get_average_price().unwrap())
+        let mut contract = AveragePrice::new("vkarnaukhov.testnet".to_string());
+        contract.set_window_size(4);
+        contract.set_last_price("BTC".to_string(), &1.0);
+        contract.set_last_price("BTC".to_string(), &4.0);
+        contract.set_last_price("BTC".to_string(), &2.0);
+        contract.set_last_price("BTC".to_string(), &3.0);
+        assert_eq!(contract.get_median_price("BTC".to_string()), Some(2.5));
     }
 }